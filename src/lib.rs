@@ -0,0 +1,1017 @@
+//! Dice notation parsing and rolling.
+//!
+//! This crate parses expressions like `3d6`, `4d6d1+2`, or `2d6+1d4+3` and
+//! evaluates them, either by rolling or by computing their exact
+//! distribution. It has no CLI or REPL dependencies, so it can be embedded
+//! in other programs (bots, game engines, etc.) that want dice rolling
+//! without shelling out.
+
+use pest;
+use pest::Parser;
+use rand;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[macro_use]
+extern crate pest_derive;
+
+pub use anyhow::Result;
+
+/// Rolls individual dice. The default `ThreadRoller` draws from the
+/// thread-local RNG; `SeededRoller` wraps a `StdRng` for reproducible
+/// rolls. Every die rolled by a `RollSpec`, `d%`/`dF` included, goes
+/// through this trait so that a seed fully determines a roll's outcome.
+pub trait Roller {
+    fn roll(&mut self, size: u64) -> u64;
+
+    /// Rolls a single Fudge/Fate die (-1, 0, or +1). Defaults to deriving
+    /// the result from `roll`, so implementors only need to provide `roll`.
+    fn roll_fudge(&mut self) -> i64 {
+        self.roll(3) as i64 - 2
+    }
+}
+
+/// The default `Roller`, drawing from `rand`'s thread-local generator.
+pub struct ThreadRoller;
+
+impl Roller for ThreadRoller {
+    fn roll(&mut self, size: u64) -> u64 {
+        rand::rng().random_range(1..=size)
+    }
+}
+
+/// A `Roller` backed by a `StdRng` seeded from a `u64`, so the same seed
+/// rolling the same expressions in the same order always produces the
+/// same results.
+pub struct SeededRoller {
+    rng: StdRng,
+}
+
+impl SeededRoller {
+    pub fn new(seed: u64) -> Self {
+        SeededRoller {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Roller for SeededRoller {
+    fn roll(&mut self, size: u64) -> u64 {
+        self.rng.random_range(1..=size)
+    }
+}
+
+/// How a `RollSpec`'s rolled dice are reduced to a result.
+#[derive(Debug, Clone, Copy)]
+pub enum Mode {
+    /// Add up the kept dice (the default).
+    Sum,
+    /// Fudge/Fate dice: each die is -1, 0, or +1, summed like `Sum`.
+    Fudge,
+    /// World-of-Darkness-style success pools: count the dice meeting
+    /// `target` under `cmp` instead of summing them.
+    Count { target: i64, cmp: Cmp },
+}
+
+/// The comparison used by a `Mode::Count` success pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Gte,
+    Lte,
+}
+
+impl Cmp {
+    fn matches(&self, value: i64, target: i64) -> bool {
+        match self {
+            Cmp::Gte => value >= target,
+            Cmp::Lte => value <= target,
+        }
+    }
+}
+
+impl fmt::Display for Cmp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Cmp::Gte => ">=",
+            Cmp::Lte => "<=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A single `NdX` dice term, e.g. `4d6d1!r1`: four six-sided dice, dropping
+/// the lowest, exploding on a max roll, and rerolling anything at or below 1.
+/// `X` may also be `F` (Fudge dice) or `%` (an alias for `d100`), and the
+/// term may end in a success-pool comparison like `s>=8`.
+#[derive(Debug)]
+pub struct RollSpec {
+    num: usize,
+    size: i64,
+    keep_high: usize,
+    keep_low: usize,
+    drop_low: usize,
+    drop_high: usize,
+    explode: bool,
+    reroll_below: Option<i64>,
+    mode: Mode,
+}
+
+/// Cap on the number of extra dice an exploding die may chain through,
+/// so a `d1!` can't spin forever.
+const MAX_EXPLODE_CHAIN: usize = 100;
+
+impl fmt::Display for RollSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut suffix = String::from("");
+        if self.keep_high > 0 {
+            suffix.push_str(&format!(" keep highest {}", self.keep_high));
+        } else if self.drop_low > 0 {
+            suffix.push_str(&format!(" drop lowest {}", self.drop_low));
+        } else if self.drop_high > 0 {
+            suffix.push_str(&format!(" drop highest {}", self.drop_high));
+        } else if self.keep_low > 0 {
+            suffix.push_str(&format!(" keep lowest {}", self.keep_low));
+        }
+
+        if self.explode {
+            suffix.push('!');
+        }
+        if let Some(n) = self.reroll_below {
+            suffix.push_str(&format!("r{}", n));
+        }
+        if let Mode::Count { target, cmp } = self.mode {
+            suffix.push_str(&format!("s{}{}", cmp, target));
+        }
+
+        let size_repr = match self.mode {
+            Mode::Fudge => "F".to_string(),
+            _ => self.size.to_string(),
+        };
+
+        write!(f, "{}d{}{}", self.num, size_repr, suffix)
+    }
+}
+
+impl RollSpec {
+    fn next_die(&self, roller: &mut dyn Roller) -> i64 {
+        match self.mode {
+            Mode::Fudge => roller.roll_fudge(),
+            _ => roller.roll(self.size as u64) as i64,
+        }
+    }
+
+    /// Rolls using the thread-local RNG. Use [`RollSpec::roll_with`] to
+    /// roll against a seeded (or otherwise custom) `Roller`.
+    pub fn roll(&self) -> Roll {
+        self.roll_with(&mut ThreadRoller)
+    }
+
+    pub fn roll_with(&self, roller: &mut dyn Roller) -> Roll {
+        let mut rolls: Vec<i64> = (0..self.num).map(|_| self.next_die(roller)).collect();
+
+        if let Some(threshold) = self.reroll_below {
+            for r in rolls.iter_mut() {
+                if *r <= threshold {
+                    *r = self.next_die(roller);
+                }
+            }
+        }
+
+        if self.explode {
+            let mut extra = Vec::new();
+            for r in rolls.iter() {
+                let mut last = *r;
+                let mut chained = 0;
+                while last == self.size && chained < MAX_EXPLODE_CHAIN {
+                    last = self.next_die(roller);
+                    extra.push(last);
+                    chained += 1;
+                }
+            }
+            rolls.append(&mut extra);
+        }
+
+        rolls.sort();
+
+        // now that we have the rolls, figure out which to keep
+
+        let len = rolls.len();
+        let range = if self.keep_high != 0 {
+            len - self.keep_high..len
+        } else if self.drop_low != 0 {
+            self.drop_low..len
+        } else if self.drop_high != 0 {
+            0..len - self.drop_high
+        } else if self.keep_low != 0 {
+            0..self.keep_low
+        } else {
+            0..len
+        };
+
+        let sum = match self.mode {
+            Mode::Count { target, cmp } => rolls[range]
+                .iter()
+                .filter(|v| cmp.matches(**v, target))
+                .count() as i64,
+            Mode::Sum | Mode::Fudge => rolls[range].iter().sum(),
+        };
+
+        return Roll {
+            rolls,
+            sum,
+            mode: self.mode,
+        };
+    }
+
+    /// The exact probability distribution of this term's result, computed
+    /// without rolling, keyed by outcome (a success count for `Mode::Count`).
+    ///
+    /// Errors if this term explodes *and* either keeps/drops dice or counts
+    /// successes: `roll_with` treats each exploded die as a separate die
+    /// added to the pool, so the pool size (and, for a success pool, the
+    /// number of dice counted) varies from roll to roll, which this method
+    /// has no exact closed form for.
+    pub fn distribution(&self) -> Result<BTreeMap<i64, f64>> {
+        let keeps_or_drops =
+            self.keep_high > 0 || self.keep_low > 0 || self.drop_low > 0 || self.drop_high > 0;
+        if self.explode && (keeps_or_drops || matches!(self.mode, Mode::Count { .. })) {
+            return Err(anyhow::anyhow!(
+                "can't compute exact odds for '{}': exploding dice combined with keep/drop or success pools aren't supported by --stats",
+                self
+            ));
+        }
+
+        let die = self.single_die_distribution();
+
+        let drop_low_n = if self.drop_low > 0 {
+            self.drop_low
+        } else if self.keep_high > 0 {
+            self.num - self.keep_high
+        } else {
+            0
+        };
+        let drop_high_n = if self.drop_high > 0 {
+            self.drop_high
+        } else if self.keep_low > 0 {
+            self.num - self.keep_low
+        } else {
+            0
+        };
+
+        if let Mode::Count { target, cmp } = self.mode {
+            if !keeps_or_drops {
+                let p_success: f64 = die
+                    .iter()
+                    .filter(|(&v, _)| cmp.matches(v, target))
+                    .map(|(_, p)| p)
+                    .sum();
+                return Ok((0..=self.num)
+                    .map(|c| (c as i64, binomial_prob(self.num, c, p_success)))
+                    .collect());
+            }
+            let success = move |v: i64| i64::from(cmp.matches(v, target));
+            if drop_high_n > 0 {
+                let mirrored: BTreeMap<i64, f64> = die.iter().map(|(v, p)| (-v, *p)).collect();
+                return Ok(drop_lowest_by(&mirrored, self.num, drop_high_n, move |v| {
+                    success(-v)
+                }));
+            }
+            return Ok(drop_lowest_by(&die, self.num, drop_low_n, success));
+        }
+
+        if !keeps_or_drops {
+            let mut dist = BTreeMap::from([(0i64, 1.0)]);
+            for _ in 0..self.num {
+                dist = convolve(&dist, &die);
+            }
+            return Ok(dist);
+        }
+
+        if drop_high_n > 0 {
+            let mirrored: BTreeMap<i64, f64> = die.iter().map(|(v, p)| (-v, *p)).collect();
+            let dropped = drop_lowest(&mirrored, self.num, drop_high_n);
+            return Ok(dropped.into_iter().map(|(s, p)| (-s, p)).collect());
+        }
+        Ok(drop_lowest(&die, self.num, drop_low_n))
+    }
+
+    /// The distribution of a single die's contributed value, folding in
+    /// reroll-below and exploding semantics.
+    fn single_die_distribution(&self) -> BTreeMap<i64, f64> {
+        let size = self.size;
+        let mut die: BTreeMap<i64, f64> = match self.mode {
+            Mode::Fudge => {
+                let p = 1.0 / 3.0;
+                BTreeMap::from([(-1, p), (0, p), (1, p)])
+            }
+            _ => {
+                let p = 1.0 / size as f64;
+                (1..=size).map(|v| (v, p)).collect()
+            }
+        };
+        // Dice added by an explosion are never rerolled (see `roll_with`).
+        let plain = die.clone();
+
+        if let Some(threshold) = self.reroll_below {
+            let reroll_mass: f64 = die
+                .iter()
+                .filter(|(&v, _)| v <= threshold)
+                .map(|(_, p)| p)
+                .sum();
+            die = die
+                .iter()
+                .map(|(&v, &p)| {
+                    let base = if v > threshold { p } else { 0.0 };
+                    (v, base + reroll_mass * p)
+                })
+                .collect();
+        }
+
+        if self.explode {
+            let mut exploded: BTreeMap<i64, f64> = BTreeMap::new();
+            let mut carry_prob = 1.0;
+            let mut carry_sum = 0i64;
+            // The first die plus up to MAX_EXPLODE_CHAIN extra dice.
+            for chain in 0..=MAX_EXPLODE_CHAIN {
+                let last = chain == MAX_EXPLODE_CHAIN;
+                let step = if chain == 0 { &die } else { &plain };
+                for (&v, &vp) in step.iter() {
+                    if v == size && !last {
+                        continue; // keeps chaining; folded in below
+                    }
+                    *exploded.entry(carry_sum + v).or_insert(0.0) += carry_prob * vp;
+                }
+                carry_prob *= *step.get(&size).unwrap_or(&0.0);
+                carry_sum += size;
+            }
+            die = exploded;
+        }
+
+        die
+    }
+}
+
+/// Sum-of-two-independent-distributions convolution: for every pair of
+/// outcomes, the probability of their sum is the product of their
+/// probabilities.
+fn convolve(a: &BTreeMap<i64, f64>, b: &BTreeMap<i64, f64>) -> BTreeMap<i64, f64> {
+    let mut result = BTreeMap::new();
+    for (&av, &ap) in a {
+        for (&bv, &bp) in b {
+            *result.entry(av + bv).or_insert(0.0) += ap * bp;
+        }
+    }
+    result
+}
+
+fn binomial_prob(n: usize, k: usize, q: f64) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    let mut coeff = 1.0f64;
+    for i in 0..k {
+        coeff = coeff * (n - i) as f64 / (i + 1) as f64;
+    }
+    coeff * q.powi(k as i32) * (1.0 - q).powi((n - k) as i32)
+}
+
+/// Distribution of the sum of `num` iid dice drawn from `die`, after
+/// dropping the lowest `drop_n` of them. Dice are assigned to outcomes in
+/// ascending order via sequential binomial splitting, which reproduces the
+/// exact multinomial distribution over per-value counts, while a running
+/// budget of `drop_n` consumes the lowest-valued dice first.
+fn drop_lowest(die: &BTreeMap<i64, f64>, num: usize, drop_n: usize) -> BTreeMap<i64, f64> {
+    drop_lowest_by(die, num, drop_n, |value| value)
+}
+
+/// As [`drop_lowest`], but each kept die contributes `metric(value)` instead
+/// of its face value, e.g. a 0/1 success indicator for `Mode::Count`. Dice
+/// are still ordered (and dropped) by their actual face value, not by the
+/// metric.
+fn drop_lowest_by(
+    die: &BTreeMap<i64, f64>,
+    num: usize,
+    drop_n: usize,
+    metric: impl Fn(i64) -> i64,
+) -> BTreeMap<i64, f64> {
+    let mut states: BTreeMap<(usize, usize), BTreeMap<i64, f64>> = BTreeMap::new();
+    states.insert((num, drop_n), BTreeMap::from([(0i64, 1.0)]));
+
+    let mut remaining_mass = 1.0;
+    for (&value, &p) in die.iter() {
+        let q = if remaining_mass > 0.0 {
+            p / remaining_mass
+        } else {
+            0.0
+        };
+        let mut next_states: BTreeMap<(usize, usize), BTreeMap<i64, f64>> = BTreeMap::new();
+        for ((dice_remaining, budget), sums) in states.iter() {
+            for c in 0..=*dice_remaining {
+                let binom = binomial_prob(*dice_remaining, c, q);
+                if binom == 0.0 {
+                    continue;
+                }
+                let dropped_here = c.min(*budget);
+                let counted = c - dropped_here;
+                let new_budget = budget - dropped_here;
+                let new_remaining = dice_remaining - c;
+                let added_sum = counted as i64 * metric(value);
+                let entry = next_states
+                    .entry((new_remaining, new_budget))
+                    .or_insert_with(BTreeMap::new);
+                for (&s, &sp) in sums.iter() {
+                    *entry.entry(s + added_sum).or_insert(0.0) += sp * binom;
+                }
+            }
+        }
+        states = next_states;
+        remaining_mass -= p;
+    }
+
+    let mut result = BTreeMap::new();
+    for (_, sums) in states {
+        for (s, p) in sums {
+            *result.entry(s).or_insert(0.0) += p;
+        }
+    }
+    result
+}
+
+/// The rolled dice and their result for a single `RollSpec`.
+#[derive(Debug)]
+pub struct Roll {
+    rolls: Vec<i64>,
+    sum: i64,
+    mode: Mode,
+}
+
+impl Roll {
+    pub fn rolls(&self) -> &[i64] {
+        &self.rolls
+    }
+
+    pub fn sum(&self) -> i64 {
+        self.sum
+    }
+}
+
+impl fmt::Display for Roll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Count { .. } => write!(
+                f,
+                "{:?}\t{} success{}",
+                self.rolls,
+                self.sum,
+                if self.sum == 1 { "" } else { "es" }
+            ),
+            Mode::Sum | Mode::Fudge => write!(f, "{:?}\t{}", self.rolls, self.sum),
+        }
+    }
+}
+
+/// An arithmetic expression over dice terms and constants, e.g. `2d6+1d4+3`
+/// or `(4d6d1)*2`.
+#[derive(Debug)]
+pub enum Expr {
+    Dice(RollSpec),
+    Const(i64),
+    BinOp(Box<Expr>, Op, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Dice(spec) => write!(f, "{}", spec),
+            Expr::Const(n) => write!(f, "{}", n),
+            Expr::BinOp(l, op, r) => write!(f, "{} {} {}", l, op, r),
+        }
+    }
+}
+
+impl Expr {
+    /// Evaluates using the thread-local RNG. Use [`Expr::roll_with`] to
+    /// roll against a seeded (or otherwise custom) `Roller`.
+    pub fn roll(&self) -> Result<Evaluation> {
+        self.roll_with(&mut ThreadRoller)
+    }
+
+    pub fn roll_with(&self, roller: &mut dyn Roller) -> Result<Evaluation> {
+        match self {
+            Expr::Const(n) => Ok(Evaluation {
+                sum: *n,
+                detail: vec![],
+            }),
+            Expr::Dice(spec) => {
+                let r = spec.roll_with(roller);
+                Ok(Evaluation {
+                    sum: r.sum,
+                    detail: vec![r],
+                })
+            }
+            Expr::BinOp(l, op, r) => {
+                let mut le = l.roll_with(roller)?;
+                let re = r.roll_with(roller)?;
+                let sum = match op {
+                    Op::Add => le.sum + re.sum,
+                    Op::Sub => le.sum - re.sum,
+                    Op::Mul => le.sum * re.sum,
+                    Op::Div if re.sum != 0 => le.sum / re.sum,
+                    Op::Div => return Err(anyhow::anyhow!("division by zero in '{}'", self)),
+                };
+                le.detail.extend(re.detail);
+                Ok(Evaluation {
+                    sum,
+                    detail: le.detail,
+                })
+            }
+        }
+    }
+
+    /// The exact probability distribution of this expression's result,
+    /// computed without rolling.
+    pub fn distribution(&self) -> Result<BTreeMap<i64, f64>> {
+        match self {
+            Expr::Const(n) => Ok(BTreeMap::from([(*n, 1.0)])),
+            Expr::Dice(spec) => spec.distribution(),
+            Expr::BinOp(l, op, r) => {
+                let ld = l.distribution()?;
+                let rd = r.distribution()?;
+                let mut result = BTreeMap::new();
+                for (&lv, &lp) in &ld {
+                    for (&rv, &rp) in &rd {
+                        let v = match op {
+                            Op::Add => lv + rv,
+                            Op::Sub => lv - rv,
+                            Op::Mul => lv * rv,
+                            Op::Div if rv != 0 => lv / rv,
+                            Op::Div => continue,
+                        };
+                        *result.entry(v).or_insert(0.0) += lp * rp;
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+/// Summary statistics over a probability distribution as produced by
+/// `RollSpec::distribution` or `Expr::distribution`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub mean: f64,
+    pub min: i64,
+    pub max: i64,
+    pub stddev: f64,
+}
+
+/// Computes mean, min, max and standard deviation over a probability
+/// distribution. Errors if `dist` is empty, which happens for expressions
+/// whose every outcome divides by zero, e.g. `1d6/0`.
+pub fn summarize(dist: &BTreeMap<i64, f64>) -> Result<Stats> {
+    let min = *dist
+        .keys()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("expression has no possible outcomes"))?;
+    let max = *dist.keys().last().expect("checked non-empty above");
+    let mean: f64 = dist.iter().map(|(&v, &p)| v as f64 * p).sum();
+    let variance: f64 = dist
+        .iter()
+        .map(|(&v, &p)| p * (v as f64 - mean).powi(2))
+        .sum();
+    Ok(Stats {
+        mean,
+        min,
+        max,
+        stddev: variance.sqrt(),
+    })
+}
+
+/// The result of evaluating an `Expr`: the final sum plus the rolled detail
+/// of every `Dice` node encountered along the way, in evaluation order.
+pub struct Evaluation {
+    sum: i64,
+    detail: Vec<Roll>,
+}
+
+impl Evaluation {
+    pub fn sum(&self) -> i64 {
+        self.sum
+    }
+
+    pub fn detail(&self) -> &[Roll] {
+        &self.detail
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for d in &self.detail {
+            write!(f, "{:?} ", d.rolls)?;
+        }
+        if self
+            .detail
+            .iter()
+            .any(|d| matches!(d.mode, Mode::Count { .. }))
+        {
+            write!(
+                f,
+                "\t{} success{}",
+                self.sum,
+                if self.sum == 1 { "" } else { "es" }
+            )
+        } else {
+            write!(f, "\t{}", self.sum)
+        }
+    }
+}
+
+#[derive(Parser)]
+#[grammar = "expr.pest"]
+struct ExprParser;
+
+pub fn parse<S: Into<String>>(it: S) -> Result<Expr> {
+    let s: &str = &it.into();
+    let expression = ExprParser::parse(Rule::expression, s)
+        .map_err(|e| anyhow::anyhow!("Failed to parse expression '{}': {}", s, e))?
+        .next()
+        .expect("Unable to read expression");
+
+    let add_expr = expression
+        .into_inner()
+        .next()
+        .expect("Unable to read expression body");
+
+    return parse_add_expr(add_expr);
+}
+
+fn parse_add_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
+    let mut inner = pair.into_inner();
+    let mut expr = parse_mul_expr(inner.next().expect("Unable to read addend"))?;
+    while let Some(op) = inner.next() {
+        let op = match op.as_rule() {
+            Rule::add_op => Op::Add,
+            Rule::sub_op => Op::Sub,
+            _ => panic!("unexpected token! {}", op),
+        };
+        let rhs = parse_mul_expr(inner.next().expect("Unable to read addend"))?;
+        expr = Expr::BinOp(Box::new(expr), op, Box::new(rhs));
+    }
+    return Ok(expr);
+}
+
+fn parse_mul_expr(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
+    let mut inner = pair.into_inner();
+    let mut expr = parse_atom(inner.next().expect("Unable to read factor"))?;
+    while let Some(op) = inner.next() {
+        let op = match op.as_rule() {
+            Rule::mul_op => Op::Mul,
+            Rule::div_op => Op::Div,
+            _ => panic!("unexpected token! {}", op),
+        };
+        let rhs = parse_atom(inner.next().expect("Unable to read factor"))?;
+        expr = Expr::BinOp(Box::new(expr), op, Box::new(rhs));
+    }
+    return Ok(expr);
+}
+
+fn parse_atom(pair: pest::iterators::Pair<Rule>) -> Result<Expr> {
+    match pair.as_rule() {
+        Rule::dice => Ok(Expr::Dice(parse_dice(pair)?)),
+        Rule::integer => {
+            let n = pair
+                .as_str()
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid constant '{}': {}", pair.as_str(), e))?;
+            Ok(Expr::Const(n))
+        }
+        Rule::add_expr => parse_add_expr(pair),
+        _ => panic!("unexpected token! {}", pair),
+    }
+}
+
+fn parse_dice(pair: pest::iterators::Pair<Rule>) -> Result<RollSpec> {
+    let mut r = RollSpec {
+        num: 1,
+        size: 0,
+        drop_low: 0,
+        drop_high: 0,
+        keep_low: 0,
+        keep_high: 0,
+        explode: false,
+        reroll_below: None,
+        mode: Mode::Sum,
+    };
+    let mut pending_cmp: Option<Cmp> = None;
+
+    macro_rules! parse_field {
+        ($field:expr, $part:expr, $err_msg:expr) => {
+            $field = $part
+                .as_str()
+                .parse()
+                .map_err(|e| anyhow::anyhow!($err_msg, $part.as_str(), e))?
+        };
+    }
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::n_dice => {
+                parse_field!(r.num, part, "Invalid number of dice '{}': {}");
+            }
+            Rule::die_size => {
+                parse_field!(r.size, part, "Invalid die size '{}': {}");
+            }
+            Rule::percent_marker => {
+                r.size = 100;
+            }
+            Rule::fudge_marker => {
+                r.mode = Mode::Fudge;
+                r.size = 1;
+            }
+            Rule::n_low_to_drop => {
+                parse_field!(
+                    r.drop_low,
+                    part,
+                    "Invalid number of low dice to drop '{}': {}"
+                );
+            }
+            Rule::n_low_to_keep => {
+                parse_field!(
+                    r.keep_low,
+                    part,
+                    "Invalid number of low dice to keep '{}': {}"
+                );
+            }
+            Rule::n_high_to_keep => {
+                parse_field!(
+                    r.keep_high,
+                    part,
+                    "Invalid number of high dice to keep '{}': {}"
+                );
+            }
+            Rule::n_high_to_drop => {
+                parse_field!(
+                    r.drop_high,
+                    part,
+                    "Invalid number of high dice to drop '{}': {}"
+                );
+            }
+            Rule::explode => {
+                r.explode = true;
+            }
+            Rule::reroll_below => {
+                r.reroll_below = Some(part.as_str().parse().map_err(|e| {
+                    anyhow::anyhow!("Invalid reroll threshold '{}': {}", part.as_str(), e)
+                })?);
+            }
+            Rule::success_gte => {
+                pending_cmp = Some(Cmp::Gte);
+            }
+            Rule::success_lte => {
+                pending_cmp = Some(Cmp::Lte);
+            }
+            Rule::success_target => {
+                let target = part.as_str().parse().map_err(|e| {
+                    anyhow::anyhow!("Invalid success target '{}': {}", part.as_str(), e)
+                })?;
+                let cmp = pending_cmp
+                    .take()
+                    .expect("success_target without a preceding comparator");
+                r.mode = Mode::Count { target, cmp };
+            }
+            _ => panic!("unexpected token! {}", part),
+        }
+    }
+
+    return Ok(r);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_roll_sizes(size in 1..10000000) {
+            let rs = ThreadRoller.roll(size as u64);
+            assert!(rs >= 1);
+            assert!(rs <= size as u64);
+        }
+    }
+
+    #[test]
+    fn test_parse_3d6() {
+        let expr = parse("3d6").expect("3d6 should parse");
+        match expr {
+            Expr::Dice(spec) => {
+                assert_eq!(spec.num, 3);
+                assert_eq!(spec.size, 6);
+            }
+            _ => panic!("expected a Dice expression, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn test_parse_d6() {
+        let expr = parse("d6").expect("d6 should parse");
+        match expr {
+            Expr::Dice(spec) => {
+                assert_eq!(spec.num, 1);
+                assert_eq!(spec.size, 6);
+            }
+            _ => panic!("expected a Dice expression, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn test_parse_6() {
+        match parse("6").expect("6 should parse") {
+            Expr::Const(n) => assert_eq!(n, 6),
+            other => panic!("expected a Const expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_explode() {
+        match parse("3d6!").expect("3d6! should parse") {
+            Expr::Dice(spec) => assert!(spec.explode),
+            other => panic!("expected a Dice expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_reroll() {
+        match parse("4d6r1").expect("4d6r1 should parse") {
+            Expr::Dice(spec) => assert_eq!(spec.reroll_below, Some(1)),
+            other => panic!("expected a Dice expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_garbage() {
+        assert!(parse("3d").is_err());
+    }
+
+    #[test]
+    fn test_parse_compound() {
+        let expr = parse("2d6+1d4+3").expect("2d6+1d4+3 should parse");
+        assert!(matches!(expr, Expr::BinOp(..)));
+        let dist = expr.distribution().unwrap();
+        assert!((dist.values().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_parens() {
+        let expr = parse("(4d6d1)*2").expect("(4d6d1)*2 should parse");
+        assert!(matches!(expr, Expr::BinOp(..)));
+        let dist = expr.distribution().unwrap();
+        assert!((dist.values().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_distribution_d6_sums_to_one() {
+        let expr = parse("d6").unwrap();
+        let dist = expr.distribution().unwrap();
+        let total: f64 = dist.values().sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert_eq!(*dist.keys().next().unwrap(), 1);
+        assert_eq!(*dist.keys().last().unwrap(), 6);
+    }
+
+    #[test]
+    fn test_distribution_2d6_mean() {
+        let expr = parse("2d6").unwrap();
+        let dist = expr.distribution().unwrap();
+        let stats = summarize(&dist).unwrap();
+        assert!((stats.mean - 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_summarize_empty_distribution_is_an_error() {
+        let expr = parse("1d6/0").unwrap();
+        assert!(summarize(&expr.distribution().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_distribution_rejects_explode_with_keep_drop() {
+        let expr = parse("4d6d1!").unwrap();
+        assert!(expr.distribution().is_err());
+    }
+
+    #[test]
+    fn test_distribution_rejects_exploding_success_pool() {
+        let expr = parse("3d6!s>=6").unwrap();
+        assert!(expr.distribution().is_err());
+    }
+
+    #[test]
+    fn test_success_pool_distribution_honors_keep_drop() {
+        // Keep the higher of two d6, success on >= 4: by brute enumeration of
+        // all 36 rolls, 27 have max(a, b) >= 4.
+        let expr = parse("2d6k1s>=4").unwrap();
+        let dist = expr.distribution().unwrap();
+        let total: f64 = dist.values().sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!((dist[&1] - 27.0 / 36.0).abs() < 1e-9);
+        assert!((dist[&0] - 9.0 / 36.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_fudge() {
+        match parse("4dF").expect("4dF should parse") {
+            Expr::Dice(spec) => {
+                assert_eq!(spec.num, 4);
+                assert!(matches!(spec.mode, Mode::Fudge));
+            }
+            other => panic!("expected a Dice expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_percentile() {
+        match parse("d%").expect("d% should parse") {
+            Expr::Dice(spec) => assert_eq!(spec.size, 100),
+            other => panic!("expected a Dice expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_success_pool() {
+        match parse("5d10s>=8").expect("5d10s>=8 should parse") {
+            Expr::Dice(spec) => {
+                assert!(matches!(
+                    spec.mode,
+                    Mode::Count {
+                        target: 8,
+                        cmp: Cmp::Gte
+                    }
+                ))
+            }
+            other => panic!("expected a Dice expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_success_pool_counts_successes() {
+        let expr = parse("5d10s>=8").unwrap();
+        let eval = expr.roll().unwrap();
+        assert!(eval.sum() >= 0 && eval.sum() <= 5);
+    }
+
+    #[test]
+    fn test_seeded_roller_is_reproducible() {
+        let expr = parse("4d6d1+2").unwrap();
+        let first = expr.roll_with(&mut SeededRoller::new(42)).unwrap();
+        let second = expr.roll_with(&mut SeededRoller::new(42)).unwrap();
+        assert_eq!(first.sum(), second.sum());
+    }
+
+    #[test]
+    fn test_roll_division_by_zero_is_an_error() {
+        let expr = parse("1d6/(1d2-1d2)").unwrap();
+        // 1d2-1d2 is 0 whenever both dice agree, so keep rolling until we hit it.
+        for _ in 0..1000 {
+            if expr.roll().is_err() {
+                return;
+            }
+        }
+        panic!("expected at least one division by zero in 1000 rolls");
+    }
+
+    #[allow(dead_code)] // used in proptest, which fools the linter
+    const EXPR_PATTERN: &str = "[1-9]?{1}d[1-9]((d[1-9])|(k[1-9]))?(-[1-9])?";
+    proptest! {
+
+        #[test]
+        fn test_various_parses(expr in EXPR_PATTERN) {
+            match parse(expr) {
+                Ok(_r) => assert!(1+1 == 2),
+                Err(_e) => assert!(1+2 == 2),
+            }
+
+        }
+    }
+}
@@ -1,19 +1,15 @@
 use anyhow::Result;
 use clap::{CommandFactory, Parser as ClapParser};
-use pest;
-use pest::Parser;
-use proptest::prelude::*;
-use rand;
-use rand::Rng;
-use rayon::prelude::*;
+use dice::{parse, summarize, Evaluation, Expr, Roller, SeededRoller, ThreadRoller};
 use rustyline::DefaultEditor;
-use std::fmt;
-
-#[macro_use]
-extern crate pest_derive;
 
 fn main() -> Result<()> {
     let args = Cli::parse();
+    let mut roller: Box<dyn Roller> = match args.seed {
+        Some(seed) => Box::new(SeededRoller::new(seed)),
+        None => Box::new(ThreadRoller),
+    };
+
     if args.expression.len() == 0 {
         // start up the REPL
         let mut rl = DefaultEditor::new()?;
@@ -34,15 +30,38 @@ fn main() -> Result<()> {
                         }
                         _ => {
                             rl.add_history_entry(line)?;
-                            line.split(char::is_whitespace)
-                                .filter_map(|s| match parse(s) {
-                                    Ok(r) => Some(r),
-                                    Err(e) => {
-                                        eprintln!("{}", e);
-                                        None
+                            if let Some(rest) = line.strip_prefix("seed ") {
+                                match rest.trim().parse::<u64>() {
+                                    Ok(seed) => {
+                                        roller = Box::new(SeededRoller::new(seed));
+                                        println!("seeded with {}", seed);
                                     }
-                                })
-                                .for_each(|r| args.print(&r, &r.roll()));
+                                    Err(e) => eprintln!("invalid seed '{}': {}", rest.trim(), e),
+                                }
+                            } else if let Some(rest) = line.strip_prefix("stats ") {
+                                rest.split(char::is_whitespace)
+                                    .filter_map(|s| match parse(s) {
+                                        Ok(r) => Some(r),
+                                        Err(e) => {
+                                            eprintln!("{}", e);
+                                            None
+                                        }
+                                    })
+                                    .for_each(|r| print_stats(&r));
+                            } else {
+                                line.split(char::is_whitespace)
+                                    .filter_map(|s| match parse(s) {
+                                        Ok(r) => Some(r),
+                                        Err(e) => {
+                                            eprintln!("{}", e);
+                                            None
+                                        }
+                                    })
+                                    .for_each(|r| match r.roll_with(roller.as_mut()) {
+                                        Ok(eval) => args.print(&r, &eval),
+                                        Err(e) => eprintln!("{}", e),
+                                    });
+                            }
                         }
                     }
                 }
@@ -53,24 +72,55 @@ fn main() -> Result<()> {
     } else {
         for roll in &args.expression {
             let r = parse(roll)?;
-            args.print(&r, &r.roll());
+            if args.stats {
+                print_stats(&r);
+            } else {
+                let eval = r.roll_with(roller.as_mut())?;
+                args.print(&r, &eval);
+            }
         }
     }
 
     return Ok(());
 }
 
-fn roll_die(size: u64) -> u64 {
-    return rand::rng().random_range(1..=size);
-}
+/// Prints the exact probability distribution as a small ASCII histogram,
+/// followed by mean, min, max and standard deviation.
+fn print_stats(expr: &Expr) {
+    let dist = match expr.distribution() {
+        Ok(dist) => dist,
+        Err(e) => {
+            eprintln!("{}: {}", expr, e);
+            return;
+        }
+    };
+    let stats = match summarize(&dist) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("{}: {}", expr, e);
+            return;
+        }
+    };
+    let max_p = dist.values().cloned().fold(0.0, f64::max);
 
-proptest! {
-    #[test]
-    fn test_roll_sizes(size in 1..10000000) {
-        let rs = roll_die(size as u64);
-        assert!(rs >= 1);
-        assert!(rs <= size as u64);
+    println!("{}", expr);
+    for (outcome, p) in &dist {
+        let bar_len = if max_p > 0.0 {
+            ((p / max_p) * 40.0).round() as usize
+        } else {
+            0
+        };
+        println!(
+            "{:>6} | {:<40} {:>6.2}%",
+            outcome,
+            "#".repeat(bar_len),
+            p * 100.0
+        );
     }
+    println!(
+        "mean: {:.2}  min: {}  max: {}  stddev: {:.2}",
+        stats.mean, stats.min, stats.max, stats.stddev
+    );
 }
 
 /// # Rolls dice using a small expression language:
@@ -97,10 +147,28 @@ proptest! {
 /// Finally, you may add a constant modifier to the roll by appending `+` or `-` and
 /// a value, such as `4d6+1` `3d6-2` or `2d20K1+7`
 ///
+/// Expressions aren't limited to a single dice term. You can chain as many dice
+/// terms and constants together as you like with `+`, `-`, `*` and `/`, and group
+/// them with parentheses, e.g. `2d6+1d4+3` or `(4d6d1)*2`.
+///
 /// You can also send multiple expressions:
 ///
 /// `dice 4d6d1 4d6d1 4d6d1 4d6d1 4d6d1 4d6d1`
 ///
+/// If you'd rather know your odds before committing to a roll, pass `--stats`
+/// (or type `stats <expr>` in interactive mode) to see the exact probability
+/// distribution, mean, min, max, and standard deviation instead of a roll.
+///
+/// Beyond plain `dX` dice, you can roll `dF` (Fudge/Fate dice, each -1, 0 or
+/// +1) and `d%` (an alias for `d100`). Appending `s>=T` or `s<=T` to a term,
+/// e.g. `5d10s>=8`, turns it into a success pool: the result is the count of
+/// dice meeting the target rather than their sum.
+///
+/// Pass `--seed <n>` to roll against a reproducible RNG instead of the
+/// default entropy source, so the same seed and expressions always produce
+/// the same results. In interactive mode, `seed <n>` changes the seed used
+/// by subsequent rolls.
+///
 /// In summary:
 ///
 ///     3d6      3 x d6
@@ -118,222 +186,22 @@ struct Cli {
     /// Quiet output (just the result)
     #[structopt(short, long)]
     quiet: bool,
+    /// Show the exact probability distribution, mean and stddev instead of rolling
+    #[structopt(long)]
+    stats: bool,
+    /// Seed the RNG for reproducible rolls
+    #[structopt(long)]
+    seed: Option<u64>,
     /// Roll expressions, ie `4d6k3 4d6d1`
     expression: Vec<String>,
 }
 
 impl Cli {
-    fn print(&self, spec: &RollSpec, roll: &Roll) {
+    fn print(&self, expr: &Expr, eval: &Evaluation) {
         if self.quiet {
-            println!("{}", roll.sum)
+            println!("{}", eval.sum())
         } else {
-            println!("{}\t{}", spec, roll)
-        }
-    }
-}
-
-#[derive(Debug)]
-struct RollSpec {
-    num: usize,
-    size: i64,
-    keep_high: usize,
-    keep_low: usize,
-    drop_low: usize,
-    drop_high: usize,
-    modifier: i64,
-}
-
-impl fmt::Display for RollSpec {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut suffix = String::from("");
-        if self.keep_high > 0 {
-            suffix.push_str(&format!(" keep highest {}", self.keep_high));
-        } else if self.drop_low > 0 {
-            suffix.push_str(&format!(" drop lowest {}", self.drop_low));
-        } else if self.drop_high > 0 {
-            suffix.push_str(&format!(" drop highest {}", self.drop_high));
-        } else if self.keep_low > 0 {
-            suffix.push_str(&format!(" keep lowest {}", self.keep_low));
-        }
-
-        let mut modifier = String::from("");
-        if self.modifier > 0 {
-            modifier.push_str(&format!(" +{}", self.modifier));
-        } else if self.modifier < 0 {
-            modifier.push_str(&format!(" {}", self.modifier));
-        }
-
-        write!(f, "{}d{}{}{}", self.num, self.size, suffix, modifier)
-    }
-}
-
-impl RollSpec {
-    fn roll(&self) -> Roll {
-        let mut rolls: Vec<i64> = (0..self.num)
-            .into_par_iter()
-            .map(|_| roll_die(self.size as u64) as i64)
-            .collect();
-        rolls.par_sort();
-
-        // now that we have the rolls, figure out which to keep
-
-        let range = if self.keep_high != 0 {
-            self.num - self.keep_high..self.num
-        } else if self.drop_low != 0 {
-            self.drop_low..self.num
-        } else if self.drop_high != 0 {
-            0..self.num - self.drop_high
-        } else if self.keep_low != 0 {
-            0..self.keep_low
-        } else {
-            0..self.num
-        };
-
-        let mut sum = rolls[range].par_iter().sum();
-        sum += self.modifier;
-
-        return Roll { rolls, sum };
-    }
-}
-
-struct Roll {
-    rolls: Vec<i64>,
-    sum: i64,
-}
-
-impl fmt::Display for Roll {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}\t{}", self.rolls, self.sum)
-    }
-}
-
-#[derive(Parser)]
-#[grammar = "expr.pest"]
-pub struct ExprParser;
-
-fn parse<S: Into<String>>(it: S) -> Result<RollSpec> {
-    let s: &str = &it.into();
-    let expr = ExprParser::parse(Rule::expression, s)
-        .map_err(|e| anyhow::anyhow!("Failed to parse expression '{}': {}", s, e))?
-        .next()
-        .expect("Unable to read expression");
-
-    let mut r = RollSpec {
-        num: 1,
-        size: 0,
-        drop_low: 0,
-        drop_high: 0,
-        keep_low: 0,
-        keep_high: 0,
-        modifier: 0,
-    };
-
-    macro_rules! parse_field {
-        ($field:expr, $part:expr, $err_msg:expr) => {
-            $field = $part
-                .as_str()
-                .parse()
-                .map_err(|e| anyhow::anyhow!($err_msg, $part.as_str(), e))?
-        };
-    }
-    for part in expr.into_inner() {
-        match part.as_rule() {
-            Rule::n_dice => {
-                parse_field!(r.num, part, "Invalid number of dice '{}': {}");
-            }
-            Rule::die_size => {
-                parse_field!(r.size, part, "Invalid die size '{}': {}");
-            }
-            Rule::n_low_to_drop => {
-                parse_field!(
-                    r.drop_low,
-                    part,
-                    "Invalid number of low dice to drop '{}': {}"
-                );
-            }
-            Rule::n_low_to_keep => {
-                parse_field!(
-                    r.keep_low,
-                    part,
-                    "Invalid number of low dice to keep '{}': {}"
-                );
-            }
-            Rule::n_high_to_keep => {
-                parse_field!(
-                    r.keep_high,
-                    part,
-                    "Invalid number of high dice to keep '{}': {}"
-                );
-            }
-            Rule::n_high_to_drop => {
-                parse_field!(
-                    r.drop_high,
-                    part,
-                    "Invalid number of high dice to drop '{}': {}"
-                );
-            }
-            Rule::add_value => {
-                parse_field!(r.modifier, part, "Invalid add value '{}': {}");
-            }
-            Rule::subtract_value => {
-                r.modifier = -1
-                    * part.as_str().parse::<i64>().map_err(|e| {
-                        anyhow::anyhow!("Invalid subtract value '{}': {}", part.as_str(), e)
-                    })?
-            }
-            _ => panic!("unexpected token! {}", part),
-        }
-    }
-
-    return Ok(r);
-}
-
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_parse_3d6() {
-        match parse("3d6") {
-            Ok(r) => println!("roll: {}", r),
-            Err(e) => eprintln!("NOOOOOO {}", e),
-        }
-    }
-
-    #[test]
-    fn test_parse_d6() {
-        match parse("d6") {
-            Ok(r) => println!("roll: {}", r),
-            Err(e) => eprintln!("NOOOOOO {}", e),
-        }
-    }
-
-    #[test]
-    fn test_parse_6() {
-        match parse("6") {
-            Ok(r) => println!("roll: {}", r),
-            Err(e) => eprintln!("NOOOOOO {}", e),
-        }
-    }
-
-    #[test]
-    fn test_parse_garbage() {
-        match parse("3d8*2") {
-            Ok(_r) => assert!(1 + 1 == 3),
-            Err(_e) => assert!(1 + 2 == 3),
-        }
-    }
-
-    #[allow(dead_code)] // used in proptest, which fools the linter
-    const EXPR_PATTERN: &str = "[1-9]?{1}d[1-9]((d[1-9])|(k[1-9]))?(-[1-9])?";
-    proptest! {
-
-        #[test]
-        fn test_various_parses(expr in EXPR_PATTERN) {
-            match parse(expr) {
-                Ok(_r) => assert!(1+1 == 2),
-                Err(_e) => assert!(1+2 == 2),
-            }
-
+            println!("{}\t{}", expr, eval)
         }
     }
 }